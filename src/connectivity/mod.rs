@@ -0,0 +1,23 @@
+//! Abstraction over "how the device reaches the network", so the main
+//! loop doesn't care whether the link underneath is Wi-Fi or Thread.
+//!
+//! The Wi-Fi path is the default; enable the `thread` Cargo feature on
+//! ESP32-C6/H2 targets to swap in OpenThread instead. Both are mutually
+//! exclusive at compile time since they claim different peripherals
+//! (the Wi-Fi modem vs. the 802.15.4 radio).
+
+use anyhow::Result;
+
+#[cfg(feature = "thread")]
+pub mod thread;
+#[cfg(not(feature = "thread"))]
+pub mod wifi;
+
+/// Common interface the main loop drives to reach "netif up". Once
+/// `bring_up` returns, `initialize_time()` and the `reqwest` calls in
+/// the main loop proceed unchanged, over IPv4 or IPv6 as the underlying
+/// transport provides.
+#[async_trait::async_trait(?Send)]
+pub trait Connectivity {
+    async fn bring_up(&mut self) -> Result<()>;
+}
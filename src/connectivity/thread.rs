@@ -0,0 +1,96 @@
+//! OpenThread connectivity, for ESP32-C6/H2 boards with an 802.15.4
+//! radio. Gated behind the `thread` Cargo feature so Wi-Fi-only targets
+//! never pull this in. Requires `CONFIG_OPENTHREAD_ENABLED` and the
+//! IPv6 LWIP sdkconfig options to be turned on in sdkconfig.defaults.
+//!
+//! Unlike the Wi-Fi path, there is no on-device provisioning flow for
+//! this yet - 802.15.4 has no equivalent of an AP you can join with a
+//! phone, and this tree has no console/CLI hook to drive one either.
+//! `save_dataset` is only a persistence primitive: it writes a dataset
+//! into `thread_cfg`/`dataset` NVS for `bring_up()` to read back, and
+//! something out-of-band (a separate provisioning tool, a factory
+//! flashing step) is responsible for actually calling it. A device with
+//! nothing written there fails `bring_up()` with a clear "no dataset
+//! stored" error rather than joining the wrong network.
+
+use anyhow::Result;
+use esp_idf_svc::eventloop::EspSystemEventLoop;
+use esp_idf_svc::hal::modem::Modem;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_svc::thread::{EspThread, OperationalDataset, ThreadConfiguration};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use super::Connectivity;
+
+const NVS_NAMESPACE: &str = "thread_cfg";
+const NVS_KEY: &str = "dataset";
+
+/// The operational dataset values a freshly-provisioned device needs to
+/// join a Thread network; persisted to NVS the same way Wi-Fi
+/// credentials are in `provisioning`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Dataset {
+    pub network_key: [u8; 16],
+    pub pan_id: u16,
+    pub channel: u8,
+}
+
+fn load_dataset(nvs: &EspDefaultNvsPartition) -> Result<Option<Dataset>> {
+    let storage: EspNvs<NvsDefault> = EspNvs::new(nvs.clone(), NVS_NAMESPACE, true)?;
+    let mut buf = vec![0u8; 256];
+    let Some(bytes) = storage.get_raw(NVS_KEY, &mut buf)? else {
+        return Ok(None);
+    };
+    Ok(Some(serde_json::from_slice(bytes)?))
+}
+
+/// Persists a dataset obtained out-of-band so a subsequent `bring_up()`
+/// has something to join with. This is a storage primitive, not a
+/// provisioning flow - see the module doc.
+pub fn save_dataset(nvs: &EspDefaultNvsPartition, dataset: &Dataset) -> Result<()> {
+    let mut storage: EspNvs<NvsDefault> = EspNvs::new(nvs.clone(), NVS_NAMESPACE, true)?;
+    let bytes = serde_json::to_vec(dataset)?;
+    storage.set_raw(NVS_KEY, &bytes)?;
+    Ok(())
+}
+
+pub struct ThreadConnectivity<'a> {
+    thread: EspThread<'static>,
+    nvs: &'a EspDefaultNvsPartition,
+}
+
+impl<'a> ThreadConnectivity<'a> {
+    pub fn new(modem: Modem, sysloop: EspSystemEventLoop, nvs: &'a EspDefaultNvsPartition) -> Result<Self> {
+        let thread = EspThread::new(modem, sysloop, Some(nvs.clone()))?;
+        Ok(Self { thread, nvs })
+    }
+
+    /// Stands in for the Wi-Fi MAC as this device's telemetry identity.
+    pub fn extended_address(&self) -> Result<[u8; 8]> {
+        Ok(self.thread.get_eui64()?)
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<'a> Connectivity for ThreadConnectivity<'a> {
+    async fn bring_up(&mut self) -> Result<()> {
+        let dataset = load_dataset(self.nvs)?
+            .ok_or_else(|| anyhow::Error::msg("No Thread operational dataset stored in NVS"))?;
+
+        let operational_dataset = OperationalDataset::default()
+            .set_network_key(dataset.network_key)
+            .set_pan_id(dataset.pan_id)
+            .set_channel(dataset.channel);
+
+        self.thread.set_configuration(&ThreadConfiguration::Child(operational_dataset))?;
+        info!("Joining Thread network on channel {} (PAN {:#06x})", dataset.channel, dataset.pan_id);
+
+        self.thread.start()?;
+        self.thread.wait_netif_up().await?;
+
+        info!("Thread attached, routable IPv6 address obtained");
+        Ok(())
+    }
+}
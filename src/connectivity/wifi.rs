@@ -0,0 +1,206 @@
+//! Default connectivity transport: `EspWifi` driven through the existing
+//! captive-portal provisioning flow, plus a supervisor that watches for
+//! disconnects and reconnects with backoff instead of just connecting
+//! once at startup.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use embedded_svc::wifi::Configuration;
+use esp_idf_svc::eventloop::{EspSubscription, EspSystemEventLoop, System};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_svc::wifi::{AsyncWifi, EspWifi, WifiEvent};
+
+use log::{info, warn};
+
+use crate::provisioning;
+
+use super::Connectivity;
+
+/// Delay after the first failed reconnect attempt (the first attempt
+/// itself is made immediately); doubled after each further failure up to
+/// `MAX_BACKOFF`.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Up to this many consecutive jitter milliseconds are added to each
+/// backoff so multiple devices on the same AP don't retry in lockstep.
+const BACKOFF_JITTER_MS: u64 = 500;
+/// After this many failed attempts in a row, report `ConnState::Failed`
+/// instead of `Reconnecting` (the loop keeps retrying regardless).
+const FAILED_AFTER_RETRIES: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnState {
+    Connected,
+    Reconnecting,
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectionStatus {
+    pub state: ConnState,
+    pub last_rssi: i32,
+    pub retry_count: u32,
+}
+
+impl Default for ConnectionStatus {
+    fn default() -> Self {
+        Self { state: ConnState::Connected, last_rssi: 0, retry_count: 0 }
+    }
+}
+
+pub type SharedStatus = Arc<Mutex<ConnectionStatus>>;
+
+/// In-progress backoff state between `poll_reconnect` calls; `None`
+/// means we're not currently trying to reconnect.
+struct ReconnectState {
+    next_attempt_at: Instant,
+    backoff: Duration,
+    retries: u32,
+}
+
+pub struct WifiConnectivity<'a> {
+    wifi: AsyncWifi<EspWifi<'static>>,
+    nvs: &'a EspDefaultNvsPartition,
+    status: SharedStatus,
+    disconnected: Arc<AtomicBool>,
+    reconnect: Option<ReconnectState>,
+    // Kept alive only so the subscription isn't dropped; never read directly.
+    _subscription: EspSubscription<'static, System>,
+}
+
+impl<'a> WifiConnectivity<'a> {
+    pub fn new(
+        wifi: AsyncWifi<EspWifi<'static>>,
+        nvs: &'a EspDefaultNvsPartition,
+        sysloop: &EspSystemEventLoop,
+    ) -> Result<Self> {
+        let disconnected = Arc::new(AtomicBool::new(false));
+
+        let flag = disconnected.clone();
+        let subscription = sysloop.subscribe::<WifiEvent, _>(move |event| {
+            if matches!(event, WifiEvent::StaDisconnected) {
+                flag.store(true, Ordering::SeqCst);
+            }
+        })?;
+
+        Ok(Self {
+            wifi,
+            nvs,
+            status: Arc::new(Mutex::new(ConnectionStatus::default())),
+            disconnected,
+            reconnect: None,
+            _subscription: subscription,
+        })
+    }
+
+    pub fn wifi(&self) -> &AsyncWifi<EspWifi<'static>> {
+        &self.wifi
+    }
+
+    pub fn wifi_mut(&mut self) -> &mut AsyncWifi<EspWifi<'static>> {
+        &mut self.wifi
+    }
+
+    /// Current connection health, shareable with the telemetry/logging
+    /// path without holding a borrow on `self`.
+    pub fn status(&self) -> SharedStatus {
+        self.status.clone()
+    }
+
+    /// Drives at most one reconnect step and returns - never blocks
+    /// waiting out a backoff delay, so callers (the main loop) stay free
+    /// to keep processing MQTT commands, OTA checks, etc. while a
+    /// reconnect is in progress. Cheap no-op when nothing has
+    /// disconnected and no backoff is pending, so it's fine to call
+    /// every main-loop iteration.
+    pub async fn poll_reconnect(&mut self) -> Result<()> {
+        // Drain the flag unconditionally: the driver can also emit
+        // `StaDisconnected` as part of its own roam/reassociation churn
+        // while a reconnect is already in flight, and if we only checked
+        // this when `reconnect` was `None` that event would survive
+        // until the in-flight attempt finished, then spuriously kick off
+        // a whole new reconnect cycle for a link that never actually
+        // dropped again.
+        let disconnected = self.disconnected.swap(false, Ordering::SeqCst);
+
+        if self.reconnect.is_none() {
+            if !disconnected {
+                return Ok(());
+            }
+            self.status.lock().unwrap().state = ConnState::Reconnecting;
+            self.reconnect = Some(ReconnectState {
+                next_attempt_at: Instant::now(),
+                backoff: INITIAL_BACKOFF,
+                retries: 0,
+            });
+        }
+
+        if Instant::now() < self.reconnect.as_ref().unwrap().next_attempt_at {
+            return Ok(());
+        }
+
+        let retries = self.reconnect.as_ref().unwrap().retries + 1;
+        self.status.lock().unwrap().retry_count = retries;
+
+        match self.reconnect_once().await {
+            Ok(rssi) => {
+                self.reconnect = None;
+                let mut status = self.status.lock().unwrap();
+                status.state = ConnState::Connected;
+                status.last_rssi = rssi;
+                status.retry_count = 0;
+                info!("Reconnected after {} attempt(s), RSSI {}", retries, rssi);
+            }
+            Err(e) => {
+                warn!("Reconnect attempt {} failed: {:?}", retries, e);
+                if retries >= FAILED_AFTER_RETRIES {
+                    self.status.lock().unwrap().state = ConnState::Failed;
+                }
+
+                let state = self.reconnect.as_mut().unwrap();
+                let jitter = Duration::from_millis(rand::random::<u64>() % BACKOFF_JITTER_MS);
+                state.retries = retries;
+                state.next_attempt_at = Instant::now() + state.backoff + jitter;
+                state.backoff = (state.backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn reconnect_once(&mut self) -> Result<i32> {
+        let Configuration::Client(_) = self.wifi.get_configuration()? else {
+            bail!("Wi-Fi is not in client mode, cannot reconnect");
+        };
+
+        // No scan-based BSSID pinning here: `EspWifi::scan()` is
+        // synchronous and typically takes well over a second, which
+        // would stall the whole `new_current_thread` executor - MQTT
+        // event pumping, OTA timers, everything - on every single
+        // reconnect attempt during an outage. `AsyncWifi` isn't `Send`,
+        // so it can't be moved into `spawn_blocking` either. Reconnect
+        // to the already-configured SSID instead and let the driver
+        // pick the BSSID.
+        self.wifi.connect().await?;
+        self.wifi.wait_netif_up().await?;
+        Ok(self.wifi.wifi().get_rssi().unwrap_or_default())
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<'a> Connectivity for WifiConnectivity<'a> {
+    async fn bring_up(&mut self) -> Result<()> {
+        provisioning::provision_and_connect(&mut self.wifi, self.nvs).await?;
+
+        // Provisioning churns through its own stop/configure/start cycles
+        // to get here, which can itself fire `StaDisconnected` events on
+        // the subscription installed in `new()` - clear those out now so
+        // the first `poll_reconnect()` call doesn't mistake them for a
+        // real post-boot drop and kick off a redundant reconnect.
+        self.disconnected.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+}
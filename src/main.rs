@@ -1,9 +1,7 @@
-use std::convert::TryInto;
-
 use anyhow::Result;
-use embedded_svc::wifi::{AuthMethod, ClientConfiguration, Configuration};
 
 use esp_idf_svc::eventloop::EspSystemEventLoop;
+#[cfg(not(feature = "thread"))]
 use esp_idf_svc::hal::modem::Modem;
 use esp_idf_svc::hal::peripherals::Peripherals;
 use esp_idf_svc::log::EspLogger;
@@ -12,13 +10,19 @@ use esp_idf_svc::sys::{
     esp, esp_vfs_eventfd_config_t, esp_vfs_eventfd_register
 };
 use esp_idf_svc::timer::EspTaskTimerService;
+#[cfg(not(feature = "thread"))]
 use esp_idf_svc::wifi::{AsyncWifi, EspWifi};
 
 use log::info;
 
+mod connectivity;
+mod memory;
+mod ota;
+#[cfg(not(feature = "thread"))]
+mod provisioning;
+mod telemetry;
 
-const SSID: &str = "NETGEAR13";
-const PASSWORD: &str = "royalphoenix978";
+use connectivity::Connectivity;
 
 
 fn tls_support() {
@@ -53,7 +57,15 @@ fn config_eventfd() -> Result<(), esp_idf_svc::sys::EspError> {
     esp! { unsafe { esp_vfs_eventfd_register(&config) } }
 }
 
-fn print_memory_info() {
+/// Snapshot of the figures `print_memory_info` logs, also fed to the MQTT
+/// telemetry publisher so both paths read the same numbers.
+struct MemorySnapshot {
+    total_heap: u32,
+    free_heap: u32,
+    min_free_heap: u32,
+}
+
+fn memory_snapshot() -> MemorySnapshot {
     use esp_idf_svc::sys::{
         esp_get_free_heap_size, esp_get_minimum_free_heap_size,
         heap_caps_get_total_size, MALLOC_CAP_8BIT
@@ -63,31 +75,37 @@ fn print_memory_info() {
     //
     // This is safe as long as we ensure that the ESP-IDF C API is used correctly.
     unsafe {
-        // Get the total heap size available to the application.
-        let total_heap = heap_caps_get_total_size(MALLOC_CAP_8BIT) as u32;
-        info!("Total heap size: {} bytes", total_heap);
-
-        // Get the current free heap size.
-        let free_heap = esp_get_free_heap_size();
-        info!("Current free heap size: {} bytes", free_heap);
-
-        // Get the minimum free heap size that has been observed since
-        // the application started. This is a good indicator of
-        // worst-case memory usage.
-        let min_free_heap = esp_get_minimum_free_heap_size();
-        info!("Minimum free heap size: {} bytes", min_free_heap);
-
-        // Example of a simple memory usage calculation
-        let used_heap = total_heap - free_heap;
-        info!("Currently used heap size: {} bytes", used_heap);
+        MemorySnapshot {
+            total_heap: heap_caps_get_total_size(MALLOC_CAP_8BIT) as u32,
+            free_heap: esp_get_free_heap_size(),
+            min_free_heap: esp_get_minimum_free_heap_size(),
+        }
     }
 }
 
+fn print_memory_info(snapshot: &MemorySnapshot) {
+    // Get the total heap size available to the application.
+    info!("Total heap size: {} bytes", snapshot.total_heap);
+
+    // Get the current free heap size.
+    info!("Current free heap size: {} bytes", snapshot.free_heap);
+
+    // Get the minimum free heap size that has been observed since
+    // the application started. This is a good indicator of
+    // worst-case memory usage.
+    info!("Minimum free heap size: {} bytes", snapshot.min_free_heap);
+
+    // Example of a simple memory usage calculation
+    let used_heap = snapshot.total_heap - snapshot.free_heap;
+    info!("Currently used heap size: {} bytes", used_heap);
+}
+
 fn main() -> Result<()> {
     esp_idf_svc::sys::link_patches(); // Required for compatibility
     EspLogger::initialize_default();  // Enable logging
 
     config_eventfd()?;
+    memory::register_oom_callback();
 
     tls_support();
     // Run the async main function
@@ -103,41 +121,55 @@ fn main() -> Result<()> {
 async fn async_main() -> Result<()> {
     // Take required peripherals
     let peripherals = Peripherals::take()?;
-    let modem: Modem = peripherals.modem;
     let sysloop = EspSystemEventLoop::take()?;
     let nvs = EspDefaultNvsPartition::take()?;
     let timer = EspTaskTimerService::new()?;
 
-    // Create the ESP WiFi driver
-    let wifi_driver = EspWifi::new(modem, sysloop.clone(), Some(nvs))?;
-
-    // Wrap it in AsyncWifi
-    let mut wifi = AsyncWifi::wrap(wifi_driver, sysloop, timer)?;
-
-    // Wi-Fi Configuration
-    let config = Configuration::Client(ClientConfiguration {
-        ssid: SSID.try_into().map_err(|_| anyhow::Error::msg("Error in SSID"))?,
-        password: PASSWORD.try_into().map_err(|_| anyhow::Error::msg("Error in Password"))?,
-        auth_method: AuthMethod::WPA2Personal,
-        ..Default::default()
-    });
-
-    // Set config and start
-    wifi.set_configuration(&config)?;
-    info!("Wi-Fi configuration set.");
+    // On Wi-Fi-only targets this brings the radio up through the
+    // existing captive-portal provisioning flow; building with the
+    // `thread` feature swaps in OpenThread over the 802.15.4 radio
+    // instead. Either way, by the time `bring_up` returns we have a
+    // routable IP and the rest of the loop proceeds unchanged.
+    #[cfg(not(feature = "thread"))]
+    let modem: Modem = peripherals.modem;
+    #[cfg(not(feature = "thread"))]
+    let wifi_driver = EspWifi::new(modem, sysloop.clone(), Some(nvs.clone()))?;
+    #[cfg(not(feature = "thread"))]
+    let wifi = AsyncWifi::wrap(wifi_driver, sysloop.clone(), timer)?;
+    #[cfg(not(feature = "thread"))]
+    let mut wifi = connectivity::wifi::WifiConnectivity::new(wifi, &nvs, &sysloop)?;
+    #[cfg(not(feature = "thread"))]
+    wifi.bring_up().await?;
+    #[cfg(not(feature = "thread"))]
+    let wifi_status = wifi.status();
+
+    #[cfg(feature = "thread")]
+    let mut thread = connectivity::thread::ThreadConnectivity::new(peripherals.modem, sysloop.clone(), &nvs)?;
+    #[cfg(feature = "thread")]
+    thread.bring_up().await?;
 
-    wifi.start().await?;
-    info!("Wi-Fi started.");
+    initialize_time().await?;
 
-    wifi.connect().await?;
-    info!("Wi-Fi connecting...");
+    // We made it through a full network bring-up + time sync on this image,
+    // so it's good; tell the bootloader to stop treating it as a pending
+    // rollback candidate.
+    if let Err(e) = ota::mark_running_slot_valid() {
+        info!("Could not mark running slot valid: {:?}", e);
+    }
 
-    wifi.wait_netif_up().await?;
-    info!("Wi-Fi connected!");
+    #[cfg(not(feature = "thread"))]
+    let mac = telemetry::mac_to_string(wifi.wifi().wifi().get_mac(esp_idf_svc::wifi::WifiDeviceId::Sta)?);
+    #[cfg(feature = "thread")]
+    let mac = telemetry::mac_to_string(thread.extended_address()?);
 
-    initialize_time().await?;
+    let (mut mqtt_client, mut mqtt_cmds) = telemetry::connect(&mac, &nvs).await?;
 
+    const OTA_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+    let boot_instant = tokio::time::Instant::now();
     let mut first = true;
+    let mut last_publish = tokio::time::Instant::now() - telemetry::PUBLISH_INTERVAL;
+    let mut last_ota_check = tokio::time::Instant::now();
+    let mut last_telemetry: Option<telemetry::Telemetry> = None;
     loop {
         if first {
             first = false;
@@ -147,10 +179,33 @@ async fn async_main() -> Result<()> {
             info!("Looping again...");
         }
 
-        let ip_info = wifi.wifi().sta_netif().get_ip_info()?;
-        info!("IP Info: {:?}", ip_info);
-        print_memory_info();
-        // Leaking memory to testing 
+        while let Ok(command) = mqtt_cmds.try_recv() {
+            if let Some(telemetry) = &last_telemetry {
+                telemetry::handle_command(command, telemetry);
+            }
+        }
+
+        // Drive reconnection with backoff if the sysloop told us the
+        // link dropped; cheap no-op otherwise.
+        #[cfg(not(feature = "thread"))]
+        wifi.poll_reconnect().await?;
+
+        #[cfg(not(feature = "thread"))]
+        if wifi_status.lock().unwrap().state != connectivity::wifi::ConnState::Connected {
+            info!("Wi-Fi link is down, skipping this iteration's network work");
+            continue;
+        }
+
+        #[cfg(not(feature = "thread"))]
+        {
+            let ip_info = wifi.wifi().wifi().sta_netif().get_ip_info()?;
+            info!("IP Info: {:?}", ip_info);
+            wifi_status.lock().unwrap().last_rssi = wifi.wifi().wifi().get_rssi().unwrap_or_default();
+        }
+        let snapshot = memory_snapshot();
+        print_memory_info(&snapshot);
+        memory::report_capabilities();
+        // Leaking memory to testing
         // 1. if memory tracking working as expected
         // 2. what happens on OOM
 
@@ -159,7 +214,7 @@ async fn async_main() -> Result<()> {
         // Testing if network access using standard client works.
         let response = reqwest::get("https://ifconfig.me/ip")
             .await;
-        
+
         let data = match response {
             Err(e) => {
                 info!("Error in network request: {:?}", e);
@@ -171,5 +226,42 @@ async fn async_main() -> Result<()> {
         let ip = data.trim();
 
         info!("Public IP: {}", ip);
+
+        if last_publish.elapsed() >= telemetry::PUBLISH_INTERVAL {
+            #[cfg(not(feature = "thread"))]
+            let rssi = wifi_status.lock().unwrap().last_rssi;
+            #[cfg(feature = "thread")]
+            let rssi = 0;
+
+            let telemetry_data = telemetry::Telemetry {
+                total_heap: snapshot.total_heap,
+                free_heap: snapshot.free_heap,
+                min_free_heap: snapshot.min_free_heap,
+                public_ip: ip.to_owned(),
+                rssi,
+                uptime_secs: boot_instant.elapsed().as_secs(),
+            };
+
+            if let Err(e) = telemetry::publish(
+                &mut mqtt_client,
+                &mac,
+                esp_idf_svc::mqtt::client::QoS::AtLeastOnce,
+                &telemetry_data,
+            )
+            .await
+            {
+                info!("Error publishing telemetry: {:?}", e);
+            }
+
+            last_telemetry = Some(telemetry_data);
+            last_publish = tokio::time::Instant::now();
+        }
+
+        if last_ota_check.elapsed() >= OTA_CHECK_INTERVAL {
+            if let Err(e) = ota::check_and_apply_update().await {
+                info!("OTA check failed: {:?}", e);
+            }
+            last_ota_check = tokio::time::Instant::now();
+        }
     }
 }
\ No newline at end of file
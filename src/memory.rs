@@ -0,0 +1,103 @@
+//! Per-capability heap tracking and OOM reporting.
+//!
+//! `MALLOC_CAP_8BIT` alone doesn't tell you much on PSRAM-equipped
+//! boards, where internal and external (SPIRAM) heaps behave very
+//! differently under pressure. This tracks `INTERNAL`, `SPIRAM` and
+//! `DMA` separately, watches fragmentation, and registers an
+//! allocation-failure callback so OOM shows up as a log line with the
+//! requested size/capability instead of a silent abort.
+
+use esp_idf_svc::sys::{
+    heap_caps_get_free_size, heap_caps_get_largest_free_block, heap_caps_get_minimum_free_size,
+    heap_caps_get_total_size, heap_caps_register_failed_alloc_callback, MALLOC_CAP_DMA,
+    MALLOC_CAP_INTERNAL, MALLOC_CAP_SPIRAM,
+};
+
+use log::{info, warn};
+
+/// Warn once free heap in a capability drops below this many bytes.
+const LOW_WATER_THRESHOLD: u32 = 20 * 1024;
+
+#[derive(Debug, Clone, Copy)]
+pub struct CapStats {
+    pub name: &'static str,
+    pub total: u32,
+    pub free: u32,
+    pub largest_free_block: u32,
+    pub min_free: u32,
+}
+
+impl CapStats {
+    /// Ratio of the largest contiguous free block to total free heap;
+    /// closer to 0 means the free heap is badly fragmented.
+    pub fn fragmentation(&self) -> f32 {
+        if self.free == 0 {
+            return 0.0;
+        }
+        self.largest_free_block as f32 / self.free as f32
+    }
+}
+
+fn capability_stats(name: &'static str, caps: u32) -> CapStats {
+    // Safe as long as `caps` is one of the MALLOC_CAP_* bitflags, which
+    // it always is here since callers only pass the constants below.
+    unsafe {
+        CapStats {
+            name,
+            total: heap_caps_get_total_size(caps) as u32,
+            free: heap_caps_get_free_size(caps) as u32,
+            largest_free_block: heap_caps_get_largest_free_block(caps) as u32,
+            min_free: heap_caps_get_minimum_free_size(caps) as u32,
+        }
+    }
+}
+
+/// Logs totals/free/largest-free-block/fragmentation for the internal,
+/// SPIRAM and DMA capable heaps, warning when any of them crosses
+/// `LOW_WATER_THRESHOLD` free bytes.
+pub fn report_capabilities() {
+    for stats in [
+        capability_stats("internal", MALLOC_CAP_INTERNAL),
+        capability_stats("spiram", MALLOC_CAP_SPIRAM),
+        capability_stats("dma", MALLOC_CAP_DMA),
+    ] {
+        info!(
+            "[{}] total={}B free={}B largest_free_block={}B min_free={}B fragmentation={:.2}",
+            stats.name,
+            stats.total,
+            stats.free,
+            stats.largest_free_block,
+            stats.min_free,
+            stats.fragmentation()
+        );
+
+        if stats.total > 0 && stats.free < LOW_WATER_THRESHOLD {
+            warn!(
+                "Low free heap in '{}' capability: {}B free (threshold {}B)",
+                stats.name, stats.free, LOW_WATER_THRESHOLD
+            );
+        }
+    }
+}
+
+unsafe extern "C" fn on_alloc_failed(size: usize, caps: u32, function_name: *const std::ffi::c_char) {
+    let function_name = if function_name.is_null() {
+        "<unknown>".to_owned()
+    } else {
+        std::ffi::CStr::from_ptr(function_name)
+            .to_string_lossy()
+            .into_owned()
+    };
+    warn!(
+        "Allocation failure: requested {} bytes with caps 0x{:x} in {}",
+        size, caps, function_name
+    );
+}
+
+/// Registers the allocation-failure callback above. Call once during
+/// startup, before the main loop begins allocating.
+pub fn register_oom_callback() {
+    unsafe {
+        heap_caps_register_failed_alloc_callback(Some(on_alloc_failed));
+    }
+}
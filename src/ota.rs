@@ -0,0 +1,96 @@
+//! HTTPS-driven OTA updates.
+//!
+//! Compares a small JSON manifest (version + image URL + sha256) against
+//! the running app descriptor, and if a different version is published,
+//! streams the image through `reqwest` straight into `EspOta` so the
+//! whole firmware image never has to sit in heap at once.
+
+use anyhow::{bail, Result};
+use esp_idf_svc::ota::EspOta;
+use esp_idf_svc::sys::esp_app_desc_t;
+use futures_util::StreamExt;
+use log::info;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// Where to fetch the update manifest from.
+const MANIFEST_URL: &str = "https://updates.example.com/esp-idf-test/manifest.json";
+
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    version: String,
+    url: String,
+    sha256: String,
+}
+
+fn running_version() -> Result<String> {
+    let app_desc: &esp_app_desc_t = unsafe { &*esp_idf_svc::sys::esp_ota_get_app_description() };
+    let version = unsafe {
+        std::ffi::CStr::from_ptr(app_desc.version.as_ptr())
+            .to_str()?
+            .to_owned()
+    };
+    Ok(version)
+}
+
+async fn fetch_manifest() -> Result<Manifest> {
+    let manifest = reqwest::get(MANIFEST_URL).await?.json::<Manifest>().await?;
+    Ok(manifest)
+}
+
+/// Checks the remote manifest against the running app descriptor and
+/// downloads + applies the update if the manifest's version differs from
+/// the running one. Versions are compared for equality only, so a
+/// manifest naming an older version is applied as well. No-op (returns
+/// `Ok(false)`) if the versions match.
+pub async fn check_and_apply_update() -> Result<bool> {
+    let manifest = fetch_manifest().await?;
+    let current = running_version()?;
+
+    if manifest.version == current {
+        info!("Firmware is up to date ({})", current);
+        return Ok(false);
+    }
+
+    info!("Update available: {} -> {}", current, manifest.version);
+    apply_update(&manifest).await?;
+    Ok(true)
+}
+
+async fn apply_update(manifest: &Manifest) -> Result<()> {
+    let response = reqwest::get(&manifest.url).await?;
+    let mut stream = response.bytes_stream();
+
+    let mut ota = EspOta::new()?;
+    let mut update = ota.initiate_update()?;
+    let mut hasher = Sha256::new();
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        hasher.update(&chunk);
+        update.write(&chunk)?;
+    }
+
+    let digest = hex::encode(hasher.finalize());
+    if digest != manifest.sha256 {
+        update.abort()?;
+        bail!(
+            "Downloaded image sha256 mismatch: expected {}, got {}",
+            manifest.sha256,
+            digest
+        );
+    }
+
+    update.complete()?;
+    info!("OTA update to {} applied, rebooting...", manifest.version);
+    esp_idf_svc::hal::reset::restart();
+}
+
+/// Call once the main loop has proven the new image actually works
+/// (brings the network up, syncs time) so the bootloader stops treating it
+/// as a pending rollback candidate.
+pub fn mark_running_slot_valid() -> Result<()> {
+    EspOta::new()?.mark_running_slot_valid()?;
+    info!("Running firmware slot marked valid");
+    Ok(())
+}
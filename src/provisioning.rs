@@ -0,0 +1,266 @@
+//! Captive-portal Wi-Fi provisioning.
+//!
+//! On boot we try whatever credentials are stored in NVS. If none are
+//! stored, or connecting with them keeps failing, we flip the `EspWifi`
+//! driver into mixed AP+station mode (the station side is what lets it
+//! scan for networks), serve a tiny HTML form over `EspHttpServer`, and
+//! wait for the user to pick an SSID and submit a passphrase. Once we
+//! get something that connects, it is persisted to NVS so this only has
+//! to happen once per network.
+
+use std::sync::{Arc, Condvar, Mutex};
+
+use anyhow::{bail, Result};
+use embedded_svc::http::Method;
+use embedded_svc::io::Write as _;
+use embedded_svc::wifi::{AccessPointConfiguration, AuthMethod, ClientConfiguration, Configuration};
+
+use esp_idf_svc::http::server::{EspHttpServer, Configuration as HttpServerConfiguration};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_svc::wifi::{AsyncWifi, EspWifi};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+/// NVS namespace the provisioning subsystem reads/writes credentials from.
+const NVS_NAMESPACE: &str = "wifi_cfg";
+/// Key the serialized `Credentials` blob is stored under.
+const NVS_KEY: &str = "creds";
+/// SSID advertised while waiting for the user to provision the device.
+const AP_SSID: &str = "esp-setup";
+/// How many times to retry a connect with stored/submitted credentials
+/// before falling back to (or staying in) the AP portal.
+const CONNECT_RETRIES: u32 = 3;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Credentials {
+    ssid: String,
+    password: String,
+    auth: AuthKind,
+}
+
+/// Mirrors `embedded_svc::wifi::AuthMethod` so it can derive `Serialize`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+enum AuthKind {
+    None,
+    Wpa,
+    Wpa2Personal,
+    WpaWpa2Personal,
+    Wpa3Personal,
+}
+
+impl From<AuthKind> for AuthMethod {
+    fn from(kind: AuthKind) -> Self {
+        match kind {
+            AuthKind::None => AuthMethod::None,
+            AuthKind::Wpa => AuthMethod::WPA,
+            AuthKind::Wpa2Personal => AuthMethod::WPA2Personal,
+            AuthKind::WpaWpa2Personal => AuthMethod::WPAWPA2Personal,
+            AuthKind::Wpa3Personal => AuthMethod::WPA3Personal,
+        }
+    }
+}
+
+fn load_credentials(nvs: &EspDefaultNvsPartition) -> Result<Option<Credentials>> {
+    let storage: EspNvs<NvsDefault> = EspNvs::new(nvs.clone(), NVS_NAMESPACE, true)?;
+
+    let mut buf = vec![0u8; 256];
+    let Some(bytes) = storage.get_raw(NVS_KEY, &mut buf)? else {
+        return Ok(None);
+    };
+
+    match serde_json::from_slice::<Credentials>(bytes) {
+        Ok(creds) => Ok(Some(creds)),
+        Err(e) => {
+            warn!("Stored credentials could not be parsed, ignoring: {:?}", e);
+            Ok(None)
+        }
+    }
+}
+
+fn save_credentials(nvs: &EspDefaultNvsPartition, creds: &Credentials) -> Result<()> {
+    let mut storage: EspNvs<NvsDefault> = EspNvs::new(nvs.clone(), NVS_NAMESPACE, true)?;
+    let bytes = serde_json::to_vec(creds)?;
+    storage.set_raw(NVS_KEY, &bytes)?;
+    Ok(())
+}
+
+/// Stops the driver only if it's actually running; `EspWifi::stop()` on a
+/// never-started driver returns `ESP_ERR_WIFI_NOT_STARTED`, which would
+/// otherwise break the very first call into this module on a fresh boot.
+async fn stop_if_started(wifi: &mut AsyncWifi<EspWifi<'static>>) -> Result<()> {
+    if wifi.is_started()? {
+        wifi.stop().await?;
+    }
+    Ok(())
+}
+
+async fn try_connect(
+    wifi: &mut AsyncWifi<EspWifi<'static>>,
+    creds: &Credentials,
+) -> Result<()> {
+    let config = Configuration::Client(ClientConfiguration {
+        ssid: creds.ssid.as_str().try_into().map_err(|_| anyhow::Error::msg("Error in SSID"))?,
+        password: creds
+            .password
+            .as_str()
+            .try_into()
+            .map_err(|_| anyhow::Error::msg("Error in Password"))?,
+        auth_method: creds.auth.into(),
+        ..Default::default()
+    });
+
+    stop_if_started(wifi).await?;
+    wifi.set_configuration(&config)?;
+    wifi.start().await?;
+
+    for attempt in 1..=CONNECT_RETRIES {
+        info!("Connecting to '{}' (attempt {}/{})", creds.ssid, attempt, CONNECT_RETRIES);
+        match wifi.connect().await {
+            Ok(()) => {
+                wifi.wait_netif_up().await?;
+                return Ok(());
+            }
+            Err(e) => warn!("Connect attempt {} failed: {:?}", attempt, e),
+        }
+    }
+
+    bail!("Could not connect to '{}' after {} attempts", creds.ssid, CONNECT_RETRIES)
+}
+
+/// Result of waiting on the provisioning HTTP server.
+struct Submission(Mutex<Option<Credentials>>, Condvar);
+
+/// Escapes the characters that matter inside HTML text/attribute
+/// context. Scanned SSIDs come from nearby radios, not from us, so they
+/// must never be spliced into the page unescaped.
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn scan_results_html(wifi: &mut AsyncWifi<EspWifi<'static>>) -> String {
+    let networks = wifi.wifi_mut().scan().unwrap_or_default();
+    let mut options = String::new();
+    for ap in networks {
+        let ssid = html_escape(ap.ssid.as_str());
+        options.push_str(&format!(
+            "<option value=\"{ssid}\">{ssid} ({rssi} dBm)</option>",
+            ssid = ssid,
+            rssi = ap.signal_strength
+        ));
+    }
+    options
+}
+
+fn render_form(scan_options: &str) -> String {
+    format!(
+        "<html><body><h1>Connect {ap_ssid} to Wi-Fi</h1>\
+         <form method=\"POST\" action=\"/connect\">\
+         <select name=\"ssid\">{scan_options}</select><br/>\
+         <input type=\"password\" name=\"password\" placeholder=\"Passphrase\"/><br/>\
+         <input type=\"submit\" value=\"Connect\"/>\
+         </form></body></html>",
+        ap_ssid = AP_SSID,
+    )
+}
+
+/// Switches into mixed AP+station mode, serves the captive portal, and
+/// blocks until the user submits credentials that the caller should then
+/// try to connect with.
+async fn run_portal(wifi: &mut AsyncWifi<EspWifi<'static>>) -> Result<Credentials> {
+    let ap_config = Configuration::Mixed(
+        ClientConfiguration::default(),
+        AccessPointConfiguration {
+            ssid: AP_SSID.try_into().map_err(|_| anyhow::Error::msg("Error in AP SSID"))?,
+            auth_method: AuthMethod::None,
+            ..Default::default()
+        },
+    );
+
+    stop_if_started(wifi).await?;
+    wifi.set_configuration(&ap_config)?;
+    wifi.start().await?;
+    info!("Provisioning AP '{}' started, waiting for credentials...", AP_SSID);
+
+    let scan_options = scan_results_html(wifi);
+    let submission = Arc::new(Submission(Mutex::new(None), Condvar::new()));
+
+    let mut server = EspHttpServer::new(&HttpServerConfiguration::default())?;
+
+    server.fn_handler("/", Method::Get, move |req| {
+        let html = render_form(&scan_options);
+        req.into_ok_response()?.write_all(html.as_bytes())
+    })?;
+
+    {
+        let submission = submission.clone();
+        server.fn_handler("/connect", Method::Post, move |mut req| {
+            let mut body = vec![0u8; 512];
+            let read = req.read(&mut body)?;
+            let form = url::form_urlencoded::parse(&body[..read]).collect::<Vec<_>>();
+
+            let ssid = form.iter().find(|(k, _)| k == "ssid").map(|(_, v)| v.to_string());
+            let password = form.iter().find(|(k, _)| k == "password").map(|(_, v)| v.to_string());
+
+            if let (Some(ssid), Some(password)) = (ssid, password) {
+                let Submission(lock, cvar) = &*submission;
+                *lock.lock().unwrap() = Some(Credentials {
+                    ssid,
+                    password,
+                    auth: AuthKind::Wpa2Personal,
+                });
+                cvar.notify_one();
+                req.into_ok_response()?
+                    .write_all(b"Credentials received, attempting to connect...")
+            } else {
+                req.into_status_response(400)?.write_all(b"Missing ssid/password")
+            }
+        })?;
+    }
+
+    let Submission(lock, cvar) = &*submission;
+    let guard = lock.lock().unwrap();
+    let creds = cvar
+        .wait_while(guard, |creds| creds.is_none())
+        .unwrap()
+        .take()
+        .expect("condvar only wakes once credentials are set");
+
+    drop(server);
+    Ok(creds)
+}
+
+/// Provisioning -> Connecting -> Connected state machine. Returns once
+/// the device is associated and has an IP; credentials that led to a
+/// successful connection are persisted to NVS for next boot.
+pub async fn provision_and_connect(
+    wifi: &mut AsyncWifi<EspWifi<'static>>,
+    nvs: &EspDefaultNvsPartition,
+) -> Result<()> {
+    if let Some(creds) = load_credentials(nvs)? {
+        info!("Found stored credentials for '{}', trying to connect", creds.ssid);
+        if try_connect(wifi, &creds).await.is_ok() {
+            info!("Wi-Fi connected using stored credentials!");
+            return Ok(());
+        }
+        warn!("Stored credentials did not work, falling back to provisioning portal");
+    }
+
+    loop {
+        let creds = run_portal(wifi).await?;
+        match try_connect(wifi, &creds).await {
+            Ok(()) => {
+                save_credentials(nvs, &creds)?;
+                info!("Wi-Fi connected and credentials saved!");
+                return Ok(());
+            }
+            Err(e) => {
+                warn!("Submitted credentials failed to connect: {:?}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! MQTT telemetry publisher.
+//!
+//! Once the netif is up and time is synced, connects to a broker over
+//! TLS and periodically publishes the same heap/IP numbers
+//! `print_memory_info` logs to the console, as JSON on
+//! `devices/<mac>/telemetry`. Also subscribes to `devices/<mac>/cmd` so
+//! the device can be told to reboot or dump its heap remotely.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use esp_idf_svc::mqtt::client::{
+    EspAsyncMqttClient, EspAsyncMqttConnection, EspMqttClientConfig, LwtConfiguration, MqttClientConfiguration,
+    MqttProtocolVersion, QoS,
+};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_svc::sys::esp_restart;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+/// NVS namespace the configurable broker settings are read from.
+const NVS_NAMESPACE: &str = "mqtt_cfg";
+const NVS_KEY: &str = "broker";
+
+/// Compiled-in fallback used only until an operator provisions a real
+/// broker into NVS under `mqtt_cfg`/`broker` (same story as the Wi-Fi
+/// credentials in `provisioning.rs`).
+const DEFAULT_BROKER_URL: &str = "mqtts://broker.example.com:8883";
+const DEFAULT_MQTT_USER: &str = "esp-device";
+const DEFAULT_MQTT_PASS: &str = "change-me";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BrokerConfig {
+    url: String,
+    username: String,
+    password: String,
+}
+
+fn load_broker_config(nvs: &EspDefaultNvsPartition) -> Result<BrokerConfig> {
+    let storage: EspNvs<NvsDefault> = EspNvs::new(nvs.clone(), NVS_NAMESPACE, true)?;
+
+    let mut buf = vec![0u8; 256];
+    match storage.get_raw(NVS_KEY, &mut buf)? {
+        Some(bytes) => Ok(serde_json::from_slice(bytes)?),
+        None => {
+            warn!("No broker config in NVS, falling back to compiled-in default");
+            Ok(BrokerConfig {
+                url: DEFAULT_BROKER_URL.to_owned(),
+                username: DEFAULT_MQTT_USER.to_owned(),
+                password: DEFAULT_MQTT_PASS.to_owned(),
+            })
+        }
+    }
+}
+
+/// Commands accepted on `devices/<mac>/cmd`.
+#[derive(Debug)]
+pub enum Command {
+    Reboot,
+    HeapDump,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Telemetry {
+    pub total_heap: u32,
+    pub free_heap: u32,
+    pub min_free_heap: u32,
+    pub public_ip: String,
+    pub rssi: i32,
+    pub uptime_secs: u64,
+}
+
+fn topic(mac: &str, suffix: &str) -> String {
+    format!("devices/{}/{}", mac, suffix)
+}
+
+pub fn mac_to_string(mac: impl AsRef<[u8]>) -> String {
+    mac.as_ref().iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(":")
+}
+
+/// Connects to the broker with a retained last-will on
+/// `devices/<mac>/telemetry/status` (so other subscribers notice when we
+/// drop off), and subscribes to the
+/// command topic. Returns the client plus a channel that yields parsed
+/// commands as they arrive, fed by a background task draining the
+/// connection's event stream.
+pub async fn connect(
+    mac: &str,
+    nvs: &EspDefaultNvsPartition,
+) -> Result<(EspAsyncMqttClient, mpsc::UnboundedReceiver<Command>)> {
+    let broker = load_broker_config(nvs)?;
+    let lwt_topic = topic(mac, "telemetry/status");
+
+    let config = MqttClientConfiguration {
+        client_id: Some(mac),
+        username: Some(&broker.username),
+        password: Some(&broker.password),
+        protocol_version: Some(MqttProtocolVersion::V3_1_1),
+        lwt: Some(LwtConfiguration {
+            topic: &lwt_topic,
+            payload: b"offline",
+            qos: QoS::AtLeastOnce,
+            retain: true,
+        }),
+        ..Default::default()
+    };
+
+    let (mut client, mut connection) =
+        EspAsyncMqttClient::new(&broker.url, &EspMqttClientConfig::default(), &config)?;
+
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    // `subscribe().await` only resolves once the broker's SUBACK comes
+    // back through this same connection's event stream, so the pump
+    // must already be polling it before we call `subscribe` below -
+    // otherwise it has nothing to drive it and hangs forever.
+    tokio::spawn(async move { pump_events(&mut connection, &tx).await });
+
+    let cmd_topic = topic(mac, "cmd");
+    client.subscribe(&cmd_topic, QoS::AtLeastOnce).await?;
+    info!("MQTT connected to {}, subscribed to {}", broker.url, cmd_topic);
+
+    Ok((client, rx))
+}
+
+async fn pump_events(connection: &mut EspAsyncMqttConnection, tx: &mpsc::UnboundedSender<Command>) {
+    use esp_idf_svc::mqtt::client::EventPayload;
+
+    loop {
+        match connection.next().await {
+            Ok(event) => {
+                if let EventPayload::Received { data, .. } = event.payload() {
+                    let command = match data {
+                        b"reboot" => Some(Command::Reboot),
+                        b"heap_dump" => Some(Command::HeapDump),
+                        other => {
+                            warn!("Unrecognized command payload: {:?}", other);
+                            None
+                        }
+                    };
+                    if let Some(command) = command {
+                        if tx.send(command).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                warn!("MQTT connection closed: {:?}", e);
+                return;
+            }
+        }
+    }
+}
+
+pub async fn publish(client: &mut EspAsyncMqttClient, mac: &str, qos: QoS, telemetry: &Telemetry) -> Result<()> {
+    let payload = serde_json::to_vec(telemetry)?;
+    client
+        .publish(&topic(mac, "telemetry"), qos, false, &payload)
+        .await?;
+    Ok(())
+}
+
+/// Handles a command pulled off the `devices/<mac>/cmd` channel.
+pub fn handle_command(command: Command, telemetry: &Telemetry) {
+    match command {
+        Command::Reboot => {
+            info!("Reboot command received, restarting...");
+            unsafe { esp_restart() };
+        }
+        Command::HeapDump => {
+            info!("Heap dump requested: {:?}", telemetry);
+        }
+    }
+}
+
+/// How long to wait between telemetry publishes.
+pub const PUBLISH_INTERVAL: Duration = Duration::from_secs(30);